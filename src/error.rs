@@ -0,0 +1,75 @@
+//! Error type returned by [`genfut`](crate::genfut).
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// Everything that can go wrong while generating a Futhark binding crate.
+#[derive(Debug)]
+pub enum GenError {
+    /// A filesystem operation (creating a directory, copying a file,
+    /// writing a generated source file, ...) failed.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The `futhark` executable could not be found or executed.
+    FutharkNotFound(std::io::Error),
+    /// `futhark <backend> --library` exited with a failure status.
+    FutharkFailed { backend: String, stderr: String },
+    /// `bindgen` failed to generate Rust bindings from a backend's header.
+    BindgenFailed { backend: String },
+    /// Two active backends produced different `--library` manifests (entry
+    /// points or array/opaque types), meaning the generated Rust API would
+    /// differ depending on which backend's manifest it was derived from.
+    BackendMismatch { a: String, b: String },
+    /// Futhark's `--library` JSON manifest could not be parsed.
+    ManifestParse { path: PathBuf, message: String },
+}
+
+impl fmt::Display for GenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenError::Io { path, source } => write!(f, "I/O error for {:?}: {}", path, source),
+            GenError::FutharkNotFound(e) => write!(f, "could not run `futhark`: {}", e),
+            GenError::FutharkFailed { backend, stderr } => {
+                write!(f, "`futhark {}` failed:\n{}", backend, stderr)
+            }
+            GenError::BindgenFailed { backend } => write!(
+                f,
+                "bindgen failed to generate bindings for the `{}` backend",
+                backend
+            ),
+            GenError::BackendMismatch { a, b } => write!(
+                f,
+                "manifests (entry points/types) differ between the `{}` and `{}` backends",
+                a, b
+            ),
+            GenError::ManifestParse { path, message } => {
+                write!(f, "could not parse manifest {:?}: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GenError::Io { source, .. } => Some(source),
+            GenError::FutharkNotFound(source) => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Summary of what [`genfut`](crate::genfut) generated.
+///
+/// Returned on success so that callers embedding `genfut` inside a larger
+/// `build.rs` can inspect what was generated without re-parsing
+/// Futhark's output themselves.
+#[derive(Debug, Clone)]
+pub struct GenReport {
+    /// Array types listed in Futhark's `--library` JSON manifest, e.g. `futhark_f32_1d`.
+    pub array_types: Vec<String>,
+    /// Entry point signatures built from Futhark's `--library` JSON manifest.
+    pub entry_points: Vec<String>,
+}
@@ -1,83 +1,119 @@
 use std::fs::create_dir_all;
-use std::io::{self, Write};
 use std::path::PathBuf;
 use std::process::Command;
 
-pub(crate) fn gen_c(in_file: &std::path::Path, out_dir: &std::path::Path) {
+use crate::error::GenError;
+
+fn create_dir(dir: &std::path::Path) -> Result<(), GenError> {
+    create_dir_all(dir).map_err(|e| GenError::Io {
+        path: dir.to_path_buf(),
+        source: e,
+    })
+}
+
+fn run_futhark(backend: &str, in_file: &std::path::Path, out: PathBuf) -> Result<(), GenError> {
+    let output = Command::new("futhark")
+        .arg(backend)
+        .arg("--library")
+        .arg("-o")
+        .arg(out)
+        .arg(in_file)
+        .output()
+        .map_err(GenError::FutharkNotFound)?;
+    if !output.status.success() {
+        return Err(GenError::FutharkFailed {
+            backend: backend.to_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn gen_c(in_file: &std::path::Path, out_dir: &std::path::Path) -> Result<(), GenError> {
     #[cfg(feature = "sequential_c")]
     {
-        let out_path = PathBuf::from(out_dir);
-        let lib_dir = out_path.join("lib_sequential_c");
-        if let Err(e) = create_dir_all(lib_dir.clone()) {
-            eprintln!("Error creating {} ({})", lib_dir.display(), e);
-            std::process::exit(1);
-        }
-        let output = Command::new("futhark")
-            .arg("c")
-            .arg("--library")
-            .arg("-o")
-            .arg(format!(
-                "{}/lib_sequential_c/a",
-                out_dir.to_str().expect("[gen_c] out_dir failed!")
-            ))
-            .arg(in_file)
-            .output()
-            .expect("[gen_c] failed to execute process");
-        io::stdout().write_all(&output.stdout).unwrap();
-        io::stderr().write_all(&output.stderr).unwrap();
+        let lib_dir = out_dir.join("lib_sequential_c");
+        create_dir(&lib_dir)?;
+        run_futhark(
+            "c",
+            in_file,
+            lib_dir
+                .join("a")
+                .to_str()
+                .expect("[gen_c] out_dir failed!")
+                .into(),
+        )?;
     }
 
     #[cfg(feature = "cuda")]
     {
-        let out_path = PathBuf::from(out_dir);
-        let lib_dir = out_path.join("lib_cuda");
-        if let Err(e) = create_dir_all(lib_dir.clone()) {
-            eprintln!("Error creating {} ({})", lib_dir.display(), e);
-            std::process::exit(1);
-        }
-        let output = Command::new("futhark")
-            .arg("cuda")
-            .arg("--library")
-            .arg("-o")
-            .arg(format!(
-                "{}/lib_cuda/a",
-                out_dir.to_str().expect("[gen_c] out_dir failed!")
-            ))
-            .arg(in_file)
-            .output()
-            .expect("failed to execute process");
-        io::stdout().write_all(&output.stdout).unwrap();
-        io::stderr().write_all(&output.stderr).unwrap();
+        let lib_dir = out_dir.join("lib_cuda");
+        create_dir(&lib_dir)?;
+        run_futhark(
+            "cuda",
+            in_file,
+            lib_dir
+                .join("a")
+                .to_str()
+                .expect("[gen_c] out_dir failed!")
+                .into(),
+        )?;
     }
 
     #[cfg(feature = "opencl")]
     {
-        let out_path = PathBuf::from(out_dir);
-        let lib_dir = out_path.join("lib_opencl");
-        if let Err(e) = create_dir_all(lib_dir.clone()) {
-            eprintln!("Error creating {} ({})", lib_dir.display(), e);
-            std::process::exit(1);
-        }
-        let output = Command::new("futhark")
-            .arg("opencl")
-            .arg("--library")
-            .arg("-o")
-            .arg(format!(
-                "{}/lib_opencl/a",
-                out_dir.to_str().expect("[gen_c] out_dir failed!")
-            ))
-            .arg(in_file)
-            .output()
-            .expect("failed to execute process");
-        io::stdout().write_all(&output.stdout).unwrap();
-        io::stderr().write_all(&output.stderr).unwrap();
+        let lib_dir = out_dir.join("lib_opencl");
+        create_dir(&lib_dir)?;
+        run_futhark(
+            "opencl",
+            in_file,
+            lib_dir
+                .join("a")
+                .to_str()
+                .expect("[gen_c] out_dir failed!")
+                .into(),
+        )?;
     }
+
+    #[cfg(feature = "multicore")]
+    {
+        let lib_dir = out_dir.join("lib_multicore");
+        create_dir(&lib_dir)?;
+        run_futhark(
+            "multicore",
+            in_file,
+            lib_dir
+                .join("a")
+                .to_str()
+                .expect("[gen_c] out_dir failed!")
+                .into(),
+        )?;
+    }
+
+    #[cfg(feature = "ispc")]
+    {
+        let lib_dir = out_dir.join("lib_ispc");
+        create_dir(&lib_dir)?;
+        run_futhark(
+            "ispc",
+            in_file,
+            lib_dir
+                .join("a")
+                .to_str()
+                .expect("[gen_c] out_dir failed!")
+                .into(),
+        )?;
+    }
+
+    Ok(())
 }
+
 pub(crate) fn generate_bindings(
+    backend: &str,
     header: &std::path::Path,
     include_path: Option<&str>,
     out: &std::path::Path,
-) {
+) -> Result<(), GenError> {
     let bindings = bindgen::Builder::default()
         .header(
             header
@@ -86,9 +122,15 @@ pub(crate) fn generate_bindings(
         )
         .clang_args(include_path.map(|path| format!("-I{}", path)))
         .generate()
-        .expect("Unable to generate bindings");
+        .map_err(|_| GenError::BindgenFailed {
+            backend: backend.to_owned(),
+        })?;
     let out_path = PathBuf::from(out);
+    let bindings_path = out_path.join("bindings.rs");
     bindings
-        .write_to_file(out_path.join("bindings.rs"))
-        .expect("Couldn't write bindings!");
+        .write_to_file(&bindings_path)
+        .map_err(|e| GenError::Io {
+            path: bindings_path,
+            source: e,
+        })
 }
@@ -23,10 +23,14 @@
 //!        version: "0.1.0".to_string(),
 //!        license: "YOLO".to_string(),
 //!        description: "Futhark example".to_string(),
-//!    })
+//!    }).expect("genfut failed")
 //!}
 //!
 //!```
+//!
+//!`genfut` returns a [`Result<GenReport, GenError>`] rather than aborting
+//!the process, so a `build.rs` that embeds it alongside other codegen can
+//!decide for itself how to react to a recoverable failure.
 
 #![allow(unused_must_use)]
 #![allow(unused_variables)]
@@ -39,14 +43,19 @@ use std::path::PathBuf;
 use std::process::Command;
 use structopt::StructOpt;
 
-use regex::Regex;
-
 mod arrays;
 mod entry;
+mod error;
 mod genc;
+mod manifest;
+mod stub;
 use crate::arrays::gen_impl_futhark_types;
 use crate::entry::*;
+pub use crate::error::{GenError, GenReport};
 use crate::genc::*;
+use crate::manifest::{parse_manifest, Manifest};
+#[cfg(feature = "stub")]
+use crate::stub::gen_stub_c;
 
 const DEFAULT_CUDA_INCLUDE_PATH: &str = &"/opt/cuda/include";
 const DEFAULT_CUDA_LIBRARY_PATH: &str = &"/opt/cuda/lib64";
@@ -103,7 +112,7 @@ pub struct Opt {
     pub opencl_library_path: Option<String>,
 }
 
-pub fn genfut(opt: Opt) {
+pub fn genfut(opt: Opt) -> Result<GenReport, GenError> {
     let name = opt.name;
     let futhark_file = &opt.file;
     let out_dir_str: String = format!("./{}", name);
@@ -111,29 +120,38 @@ pub fn genfut(opt: Opt) {
 
     // Create with create_dir_all, because we do not want to fail if
     // the directory already exists.
-    if let Err(e) = create_dir_all(out_dir) {
-        eprintln!("Error creating {:#?} ({})", out_dir, e);
-        std::process::exit(1);
-    }
+    create_dir_all(out_dir).map_err(|e| GenError::Io {
+        path: out_dir.to_path_buf(),
+        source: e,
+    })?;
     #[cfg(not(feature = "no_futhark"))]
     {
         let mut futhark_cmd = Command::new("futhark");
         futhark_cmd.arg("pkg").arg("sync");
-        let _ = futhark_cmd.output().expect("failed: futhark pkg sync");
+        let _ = futhark_cmd.output().map_err(GenError::FutharkNotFound)?;
 
         let version_path = PathBuf::from(&out_dir).join("futhark-version.txt");
-        let mut version_file =
-            File::create(version_path).expect("could not create futhark-version.txt");
+        let mut version_file = File::create(&version_path).map_err(|e| GenError::Io {
+            path: version_path.clone(),
+            source: e,
+        })?;
         futhark_cmd.arg("--version");
-        let output = futhark_cmd.output().expect("failed: futhark --version");
+        let output = futhark_cmd.output().map_err(GenError::FutharkNotFound)?;
         version_file
             .write_all(&output.stdout)
-            .expect("failed to write Futhark version");
+            .map_err(|e| GenError::Io {
+                path: version_path,
+                source: e,
+            })?;
     }
 
     // Generate C code, Though only headerfiles are needed.
     // In general C files are generated when build at the user.
-    gen_c(&futhark_file, &out_dir);
+    // Under `no_futhark` + `stub` there is no real backend to ask for a
+    // header, so this is skipped entirely in favour of reusing the header
+    // already committed under `lib_stub/a.h` from a previous real run.
+    #[cfg(not(all(feature = "no_futhark", feature = "stub")))]
+    gen_c(&futhark_file, &out_dir)?;
 
     let active_backends: &[&str] = &[
         #[cfg(feature = "sequential_c")]
@@ -142,93 +160,112 @@ pub fn genfut(opt: Opt) {
         "cuda",
         #[cfg(feature = "opencl")]
         "opencl",
+        #[cfg(feature = "multicore")]
+        "multicore",
+        #[cfg(feature = "ispc")]
+        "ispc",
     ];
 
-    // Loop over active backends. `check_equivalent` is used to ensure
-    // that
-    let mut check_equivalent = Vec::new();
-    for &backend in active_backends {
-        // copy futhark file
-        if let Err(e) = std::fs::copy(
-            futhark_file,
-            PathBuf::from(out_dir).join(&format!("lib_{}/a.fut", backend)),
-        ) {
-            eprintln!("Error copying file: {}", e);
-            std::process::exit(1);
-        }
-
-        // Generate bindings
-        let src_dir = PathBuf::from(out_dir).join("src");
-        if let Err(e) = create_dir_all(&src_dir) {
-            eprintln!("Error creating {:#?}, ({})", src_dir, e);
-            std::process::exit(1);
-        }
-
-        if !(cfg!(target_os = "macos") && backend == "opencl") {
-            generate_bindings(
-                &PathBuf::from(out_dir).join(format!("lib_{}/a.h", backend)),
-                if backend == "cuda" {
-                    Some(
-                        opt.cuda_include_path
-                            .as_deref()
-                            .unwrap_or(DEFAULT_CUDA_INCLUDE_PATH),
-                    )
-                } else if backend == "opencl" {
-                    Some(
-                        opt.opencl_include_path
-                            .as_deref()
-                            .unwrap_or(DEFAULT_OPENCL_INCLUDE_PATH),
-                    )
-                } else {
-                    None
-                },
-                &PathBuf::from(out_dir).join("src"),
-            );
+    // Loop over active backends, parsing each one's `--library` JSON
+    // manifest (`a.json`) rather than scraping the C header with regexes.
+    // `check_equivalent` is used to ensure the manifests agree, since the
+    // generated Rust API is derived from only one of them.
+    let (array_types, entry_points, stub_headers) = if active_backends.is_empty() {
+        // Reached whenever no real backend feature is enabled (as with
+        // `stub` alone, or `no_futhark` + `stub`): there is no real
+        // backend to parse a fresh manifest from, so fall back to the one
+        // already committed from a previous real run.
+        let manifest_path = PathBuf::from(out_dir).join("lib_stub/a.json");
+        let manifest = parse_manifest(&manifest_path)?;
+        let header_path = PathBuf::from(out_dir).join("lib_stub/a.h");
+        let headers = std::fs::read_to_string(&header_path).map_err(|e| GenError::Io {
+            path: header_path,
+            source: e,
+        })?;
+        (
+            manifest.array_type_names(),
+            manifest.entry_point_signatures(),
+            headers,
+        )
+    } else {
+        let mut check_equivalent: Vec<(String, Manifest)> = Vec::new();
+        let mut last_headers = String::new();
+        for &backend in active_backends {
+            // copy futhark file
+            let fut_dest = PathBuf::from(out_dir).join(&format!("lib_{}/a.fut", backend));
+            std::fs::copy(futhark_file, &fut_dest).map_err(|e| GenError::Io {
+                path: fut_dest,
+                source: e,
+            })?;
+
+            // Generate bindings
+            let src_dir = PathBuf::from(out_dir).join("src");
+            create_dir_all(&src_dir).map_err(|e| GenError::Io {
+                path: src_dir.clone(),
+                source: e,
+            })?;
+
+            if !(cfg!(target_os = "macos") && backend == "opencl") {
+                generate_bindings(
+                    backend,
+                    &PathBuf::from(out_dir).join(format!("lib_{}/a.h", backend)),
+                    if backend == "cuda" {
+                        Some(
+                            opt.cuda_include_path
+                                .as_deref()
+                                .unwrap_or(DEFAULT_CUDA_INCLUDE_PATH),
+                        )
+                    } else if backend == "opencl" {
+                        Some(
+                            opt.opencl_include_path
+                                .as_deref()
+                                .unwrap_or(DEFAULT_OPENCL_INCLUDE_PATH),
+                        )
+                    } else {
+                        None
+                    },
+                    &PathBuf::from(out_dir).join("src"),
+                )?;
+            }
+
+            let header_path = PathBuf::from(out_dir).join(format!("lib_{}/a.h", backend));
+            let headers = std::fs::read_to_string(&header_path).map_err(|e| GenError::Io {
+                path: header_path,
+                source: e,
+            })?;
+            last_headers = headers;
+
+            let manifest_path = PathBuf::from(out_dir).join(format!("lib_{}/a.json", backend));
+            let manifest = parse_manifest(&manifest_path)?;
+
+            check_equivalent.push((backend.to_owned(), manifest));
         }
 
-        let headers =
-            std::fs::read_to_string(PathBuf::from(out_dir).join(format!("lib_{}/a.h", backend)))
-                .expect("Could not read headers");
-
-        let re_array_types = Regex::new(r"struct (futhark_.+_\d+d)\s*;").expect("Regex failed!");
-        let array_types: Vec<String> = re_array_types
-            .captures_iter(&headers)
-            .map(|c| c[1].to_owned())
-            .collect();
-        //println!("{:#?}", array_types);
-        //println!("{}", gen_impl_futhark_types(&array_types));
-
-        let re_entry_points = Regex::new(r"(?m)int futhark_entry_(.+)\(struct futhark_context \*ctx,(\s*(:?const\s*)?(:?struct\s*)?[a-z0-9_]+\s\**[a-z0-9]+,?\s?)+\);").unwrap();
-
-        let entry_points: Vec<String> = re_entry_points
-            .captures_iter(&headers)
-            .map(|c| c[0].to_owned())
-            .collect();
-
-        check_equivalent.push((
-            backend.to_owned(),
-            array_types.clone(),
-            entry_points.clone(),
-        ));
-    }
+        // verify that all active backends produced the same manifest
+        let mut check_equivalent = check_equivalent.into_iter();
+        let first = check_equivalent.next().ok_or(GenError::BackendMismatch {
+            a: "<none>".to_string(),
+            b: "<none>".to_string(),
+        })?;
+        let (_, manifest) =
+            check_equivalent.try_fold(first, |(backend, manifest), (next_backend, next_manifest)| {
+                if manifest != next_manifest {
+                    return Err(GenError::BackendMismatch {
+                        a: backend,
+                        b: next_backend,
+                    });
+                }
+                Ok((next_backend, next_manifest))
+            })?;
+        (
+            manifest.array_type_names(),
+            manifest.entry_point_signatures(),
+            last_headers,
+        )
+    };
 
-    // verify that array types and entry points match between active backends
-    let (_, array_types, entry_points) = check_equivalent
-        .into_iter()
-        .reduce(|(backend, arr, ent), (prev_backend, prev_arr, prev_ent)| {
-            assert_eq!(
-                arr, prev_arr,
-                "Array types differ between {} and {} backend",
-                backend, prev_backend
-            );
-            assert_eq!(
-                ent, prev_ent,
-                "Entry points differ between {} and {} backend",
-                backend, prev_backend
-            );
-            (backend, arr, ent)
-        })
-        .expect("at least one backend should be active");
+    #[cfg(feature = "stub")]
+    gen_stub_c(&stub_headers, &out_dir)?;
 
     // STATIC FILES
     // build.rs
@@ -257,9 +294,7 @@ pub fn genfut(opt: Opt) {
                 .as_deref()
                 .unwrap_or(DEFAULT_OPENCL_LIBRARY_PATH),
         );
-    let mut build_file =
-        File::create(PathBuf::from(out_dir).join("build.rs")).expect("File creation failed!");
-    write!(&mut build_file, "{}", static_build);
+    write_generated_file(&PathBuf::from(out_dir).join("build.rs"), &static_build)?;
 
     // Cargo.toml
     let static_cargo = format!(
@@ -270,32 +305,47 @@ pub fn genfut(opt: Opt) {
         version = &opt.version,
         license = &opt.license,
     );
-    let mut cargo_file =
-        File::create(PathBuf::from(out_dir).join("Cargo.toml")).expect("File creation failed!");
-    write!(&mut cargo_file, "{}", static_cargo);
+    write_generated_file(&PathBuf::from(out_dir).join("Cargo.toml"), &static_cargo)?;
 
     // src/context.rs
     let static_context = include_str!("static/static_context.rs");
-    let mut context_file =
-        File::create(PathBuf::from(out_dir).join("src/context.rs")).expect("File creation failed!");
-    writeln!(&mut context_file, "{}", static_context);
+    write_generated_file(
+        &PathBuf::from(out_dir).join("src/context.rs"),
+        static_context,
+    )?;
 
     // src/traits.rs
     let static_traits = include_str!("static/static_traits.rs");
-    let mut traits_file =
-        File::create(PathBuf::from(out_dir).join("src/traits.rs")).expect("File creation failed!");
-    writeln!(&mut traits_file, "{}", static_traits);
+    write_generated_file(&PathBuf::from(out_dir).join("src/traits.rs"), static_traits)?;
 
     let static_array = include_str!("static/static_array.rs");
-
-    let mut array_file =
-        File::create(PathBuf::from(out_dir).join("src/arrays.rs")).expect("File creation failed!");
-    writeln!(&mut array_file, "{}", static_array);
-    writeln!(&mut array_file, "{}", gen_impl_futhark_types(&array_types));
+    let array_contents = format!(
+        "{}\n{}\n",
+        static_array,
+        gen_impl_futhark_types(&array_types)
+    );
+    write_generated_file(
+        &PathBuf::from(out_dir).join("src/arrays.rs"),
+        &array_contents,
+    )?;
 
     let static_lib = include_str!("static/static_lib.rs");
-    let mut methods_file =
-        File::create(PathBuf::from(out_dir).join("src/lib.rs")).expect("File creation failed!");
-    writeln!(&mut methods_file, "{}", static_lib);
-    writeln!(&mut methods_file, "{}", gen_entry_points(&entry_points));
+    let lib_contents = format!("{}\n{}\n", static_lib, gen_entry_points(&entry_points));
+    write_generated_file(&PathBuf::from(out_dir).join("src/lib.rs"), &lib_contents)?;
+
+    Ok(GenReport {
+        array_types,
+        entry_points,
+    })
+}
+
+fn write_generated_file(path: &Path, contents: &str) -> Result<(), GenError> {
+    let mut file = File::create(path).map_err(|e| GenError::Io {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    write!(&mut file, "{}", contents).map_err(|e| GenError::Io {
+        path: path.to_path_buf(),
+        source: e,
+    })
 }
@@ -0,0 +1,217 @@
+//! Parser for Futhark's `--library` JSON manifest (`a.json`).
+//!
+//! Newer Futhark emits this alongside the generated header, precisely
+//! describing each entry point's inputs/outputs and the array/opaque type
+//! tables. Parsing it is more robust than scraping the same information
+//! back out of the C header with regexes, which misparses opaque types,
+//! tuples, records and multi-return entries.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::GenError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct Manifest {
+    pub entry_points: BTreeMap<String, EntryPoint>,
+    #[serde(default)]
+    pub types: BTreeMap<String, TypeInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct EntryPoint {
+    pub cfun: String,
+    pub inputs: Vec<Parameter>,
+    pub outputs: Vec<Parameter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct Parameter {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default)]
+    pub unique: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind")]
+pub(crate) enum TypeInfo {
+    #[serde(rename = "array")]
+    Array {
+        ctype: String,
+        rank: u32,
+        elemtype: String,
+    },
+    #[serde(rename = "opaque")]
+    Opaque { ctype: String },
+}
+
+/// Futhark's manifest stores `ctype` as a full C pointer type, e.g.
+/// `"struct futhark_f32_1d *"` (or `"struct futhark_opaque_foo *"` for
+/// opaques), not the bare tag. Strip the `struct`/`*` noise down to the
+/// tag itself, since that's what both `array_type_names()` and
+/// `resolve_ctype()` need to build their own pointer types from.
+fn normalize_ctype(ctype: &str) -> String {
+    ctype
+        .trim()
+        .trim_start_matches("struct")
+        .trim()
+        .trim_end_matches('*')
+        .trim()
+        .to_string()
+}
+
+/// C type for a scalar Futhark primitive, as used in `futhark_entry_*`
+/// signatures. Array and opaque types are resolved through `Manifest::types`
+/// instead, since their C name isn't derivable from the Futhark type alone.
+fn scalar_ctype(futhark_type: &str) -> Option<&'static str> {
+    match futhark_type {
+        "i8" => Some("int8_t"),
+        "i16" => Some("int16_t"),
+        "i32" => Some("int32_t"),
+        "i64" => Some("int64_t"),
+        "u8" => Some("uint8_t"),
+        "u16" => Some("uint16_t"),
+        "u32" => Some("uint32_t"),
+        "u64" => Some("uint64_t"),
+        "f16" => Some("uint16_t"),
+        "f32" => Some("float"),
+        "f64" => Some("double"),
+        "bool" => Some("bool"),
+        _ => None,
+    }
+}
+
+impl Manifest {
+    /// Array type names (e.g. `futhark_f32_1d`), in the same shape the old
+    /// `struct (futhark_.+_\d+d);` header regex used to produce.
+    pub(crate) fn array_type_names(&self) -> Vec<String> {
+        self.types
+            .values()
+            .filter_map(|t| match t {
+                TypeInfo::Array { ctype, .. } => Some(normalize_ctype(ctype)),
+                TypeInfo::Opaque { .. } => None,
+            })
+            .collect()
+    }
+
+    /// The C type for one of an entry point's `type` strings: either a
+    /// scalar primitive, or `struct <ctype>` for an array/opaque type
+    /// resolved through `self.types`.
+    fn resolve_ctype(&self, futhark_type: &str) -> String {
+        if let Some(scalar) = scalar_ctype(futhark_type) {
+            return scalar.to_string();
+        }
+        match self.types.get(futhark_type) {
+            Some(TypeInfo::Array { ctype, .. }) | Some(TypeInfo::Opaque { ctype }) => {
+                format!("struct {}", normalize_ctype(ctype))
+            }
+            None => futhark_type.to_string(),
+        }
+    }
+
+    /// One descriptive line per entry point, in the same shape the old
+    /// `int futhark_entry_*(...)` header regex used to produce, so that
+    /// existing downstream codegen keeps working unchanged. Outputs are
+    /// resolved to the real C types and come first as out-params (arrays
+    /// and opaques as `T **out`, scalars as `T *out`), matching Futhark's
+    /// C calling convention; inputs follow (arrays/opaques as `const T *in`).
+    pub(crate) fn entry_point_signatures(&self) -> Vec<String> {
+        self.entry_points
+            .values()
+            .map(|entry| {
+                let mut params = vec!["struct futhark_context *ctx".to_string()];
+                for (i, out) in entry.outputs.iter().enumerate() {
+                    let ctype = self.resolve_ctype(&out.type_name);
+                    if self.types.contains_key(&out.type_name) {
+                        params.push(format!("{} **out{}", ctype, i));
+                    } else {
+                        params.push(format!("{} *out{}", ctype, i));
+                    }
+                }
+                for (i, input) in entry.inputs.iter().enumerate() {
+                    let ctype = self.resolve_ctype(&input.type_name);
+                    if self.types.contains_key(&input.type_name) {
+                        params.push(format!("const {} *in{}", ctype, i));
+                    } else {
+                        params.push(format!("{} in{}", ctype, i));
+                    }
+                }
+                format!("int {}({});", entry.cfun, params.join(", "))
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn parse_manifest(path: &Path) -> Result<Manifest, GenError> {
+    let text = std::fs::read_to_string(path).map_err(|e| GenError::Io {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    serde_json::from_str(&text).map_err(|e| GenError::ManifestParse {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shaped like a real `futhark --library` manifest: `ctype` is the
+    /// full C pointer type (`"struct futhark_f32_1d *"`), not a bare tag.
+    const SAMPLE_MANIFEST: &str = r#"
+    {
+        "entry_points": {
+            "main": {
+                "cfun": "futhark_entry_main",
+                "inputs": [
+                    { "type": "i32" },
+                    { "type": "[]f32", "unique": false }
+                ],
+                "outputs": [
+                    { "type": "[]f32", "unique": false }
+                ]
+            }
+        },
+        "types": {
+            "[]f32": {
+                "kind": "array",
+                "ctype": "struct futhark_f32_1d *",
+                "rank": 1,
+                "elemtype": "f32"
+            }
+        }
+    }
+    "#;
+
+    fn sample_manifest() -> Manifest {
+        serde_json::from_str(SAMPLE_MANIFEST).expect("fixture manifest failed to parse")
+    }
+
+    #[test]
+    fn normalize_ctype_strips_struct_and_pointer() {
+        assert_eq!(normalize_ctype("struct futhark_f32_1d *"), "futhark_f32_1d");
+        assert_eq!(normalize_ctype("futhark_f32_1d"), "futhark_f32_1d");
+    }
+
+    #[test]
+    fn array_type_names_are_normalized() {
+        let manifest = sample_manifest();
+        assert_eq!(manifest.array_type_names(), vec!["futhark_f32_1d"]);
+    }
+
+    #[test]
+    fn entry_point_signatures_resolve_types_and_keep_outputs() {
+        let manifest = sample_manifest();
+        assert_eq!(
+            manifest.entry_point_signatures(),
+            vec!["int futhark_entry_main(struct futhark_context *ctx, \
+                 struct futhark_f32_1d **out0, int32_t in0, \
+                 const struct futhark_f32_1d *in1);"
+                .to_string()]
+        );
+    }
+}
@@ -1,62 +1,238 @@
 extern crate cc;
 
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// `CARGO_CFG_TARGET_OS`/`CARGO_CFG_TARGET_ENV` describe the crate's
+/// *target*, which is what actually matters for flag/lib selection. A bare
+/// `cfg!(target_os = ...)` here would describe the *host* this build
+/// script itself happens to run on, which is wrong for cross builds.
+fn target_os() -> String {
+    env::var("CARGO_CFG_TARGET_OS").unwrap_or_default()
+}
+
+fn target_is_msvc() -> bool {
+    env::var("CARGO_CFG_TARGET_ENV").as_deref() == Ok("msvc")
+}
+
+fn env_path(var: &str, default: &str) -> String {
+    env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
+/// GNU/Clang drivers understand `-fPIC`/`-std=...`; MSVC's `cl.exe` needs
+/// its own (differently spelled, and mostly optional) equivalents instead.
+fn apply_c_flags(build: &mut cc::Build) {
+    if target_is_msvc() {
+        build.flag_if_supported("/std:c11");
+    } else {
+        build.flag("-fPIC").flag("-std=c99");
+    }
+}
+
+fn apply_cxx_flags(build: &mut cc::Build) {
+    if target_is_msvc() {
+        build.flag("/EHsc");
+    } else {
+        build.flag("-Xcompiler").flag("-fPIC");
+        build.flag("-Xcompiler").flag("-std=c++03");
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Run `futhark <futhark_backend> --library` against `lib_dir/a.fut`,
+/// writing its output into `OUT_DIR/lib_dir` instead of the source tree.
+/// Skipped when `a.fut`'s content hash still matches the one cached from
+/// the last successful run, so unrelated rebuilds don't pay for a fresh
+/// Futhark compile every time.
+///
+/// Returns the path to the generated `a.c`.
+fn compile_futhark(lib_dir: &str, futhark_backend: &str, out_dir: &Path) -> PathBuf {
+    let fut_path = format!("./{}/a.fut", lib_dir);
+    println!("cargo:rerun-if-changed={}", fut_path);
+
+    let fut_source = std::fs::read(&fut_path).expect("failed to read .fut source");
+    let hash = fnv1a(&fut_source);
+
+    let backend_out_dir = out_dir.join(lib_dir);
+    std::fs::create_dir_all(&backend_out_dir).expect("failed to create OUT_DIR subdirectory");
+    let hash_path = backend_out_dir.join(".futhark-source-hash");
+    let c_path = backend_out_dir.join("a.c");
+
+    let up_to_date = c_path.exists()
+        && std::fs::read_to_string(&hash_path)
+            .map(|cached| cached.trim() == hash.to_string())
+            .unwrap_or(false);
+
+    if !up_to_date {
+        let status = std::process::Command::new("futhark")
+            .arg(futhark_backend)
+            .arg("--library")
+            .arg("-o")
+            .arg(backend_out_dir.join("a"))
+            .arg(&fut_path)
+            .status()
+            .expect("failed to run futhark");
+        assert!(status.success(), "futhark {} failed", futhark_backend);
+        std::fs::write(&hash_path, hash.to_string()).expect("failed to cache source hash");
+    }
+
+    c_path
+}
+
 fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR not set"));
+
+    println!("cargo:rerun-if-env-changed=CUDA_PATH");
+    println!("cargo:rerun-if-env-changed=CUDA_INCLUDE_PATH");
+    println!("cargo:rerun-if-env-changed=CUDA_LIBRARY_PATH");
+    println!("cargo:rerun-if-env-changed=OCL_ROOT");
+    println!("cargo:rerun-if-env-changed=OPENCL_INCLUDE_PATH");
+    println!("cargo:rerun-if-env-changed=OPENCL_LIBRARY_PATH");
+
     // Sequential C support
     #[cfg(feature = "sequential_c")]
-    cc::Build::new()
-        .file("./lib_sequential_c/a.c")
-        .flag("-fPIC")
-        .flag("-std=c99")
-        .shared_flag(true)
-        .warnings(false)
-        .compile("a");
+    {
+        let c_path = compile_futhark("lib_sequential_c", "c", &out_dir);
+        let mut build = cc::Build::new();
+        build.file(c_path).shared_flag(true).warnings(false);
+        apply_c_flags(&mut build);
+        build.compile("a");
+    }
 
     // CUDA support
     #[cfg(feature = "cuda")]
-    cc::Build::new()
-        .file("./lib_cuda/a.c")
-        .cuda(true)
-        .flag("-Xcompiler")
-        .flag("-fPIC")
-        .flag("-std=c++03")
-        .flag("-w")
-        .shared_flag(true)
-        .compile("a");
-    #[cfg(feature = "cuda")]
     {
-        println!("cargo:rustc-link-search=native=##CUDA_INCLUDE_PATH##");
-        println!("cargo:rustc-link-search=native=##CUDA_LIBRARY_PATH##");
+        let c_path = compile_futhark("lib_cuda", "cuda", &out_dir);
+        let mut build = cc::Build::new();
+        build.file(c_path).cuda(true).flag("-w").shared_flag(true);
+        apply_cxx_flags(&mut build);
+        build.compile("a");
+
+        // `CUDA_PATH` is the toolkit's conventional env var on both Linux
+        // and Windows; fall back to the paths baked in at generation time.
+        let cuda_root = env::var("CUDA_PATH").ok();
+        let include_path = cuda_root
+            .as_ref()
+            .map(|root| format!("{}/include", root))
+            .unwrap_or_else(|| env_path("CUDA_INCLUDE_PATH", "##CUDA_INCLUDE_PATH##"));
+        let library_path = cuda_root
+            .map(|root| {
+                if target_is_msvc() {
+                    format!("{}/lib/x64", root)
+                } else {
+                    format!("{}/lib64", root)
+                }
+            })
+            .unwrap_or_else(|| env_path("CUDA_LIBRARY_PATH", "##CUDA_LIBRARY_PATH##"));
+        println!("cargo:rustc-link-search=native={}", include_path);
+        println!("cargo:rustc-link-search=native={}", library_path);
+        // rustc appends the platform-appropriate suffix itself:
+        // `cuda.lib`/`nvrtc.lib` on *-windows-msvc, `libcuda.so`/`libnvrtc.so` elsewhere.
         println!("cargo:rustc-link-lib=dylib=cuda");
         println!("cargo:rustc-link-lib=dylib=nvrtc");
     }
 
     // OpenCL support
-
     #[cfg(feature = "opencl")]
     {
-        #[cfg(not(target_os = "macos"))]
-        {
-            cc::Build::new()
-                .file("./lib_opencl/a.c")
-                .include("##OPENCL_INCLUDE_PATH##")
-                .flag("-fPIC")
-                .flag("-std=c99")
-                .shared_flag(true)
-                .compile("a");
+        // `OCL_ROOT` is the env var used by the Khronos OpenCL SDK installer.
+        let ocl_root = env::var("OCL_ROOT").ok();
+        let include_path = ocl_root
+            .as_ref()
+            .map(|root| format!("{}/include", root))
+            .unwrap_or_else(|| env_path("OPENCL_INCLUDE_PATH", "##OPENCL_INCLUDE_PATH##"));
+        let library_path = ocl_root
+            .map(|root| {
+                if target_is_msvc() {
+                    format!("{}/lib/x64", root)
+                } else {
+                    format!("{}/lib", root)
+                }
+            })
+            .unwrap_or_else(|| env_path("OPENCL_LIBRARY_PATH", "##OPENCL_LIBRARY_PATH##"));
+
+        let c_path = compile_futhark("lib_opencl", "opencl", &out_dir);
+        let mut build = cc::Build::new();
+        build.file(c_path).include(&include_path).shared_flag(true);
+        apply_c_flags(&mut build);
+        build.compile("a");
+
+        println!("cargo:rustc-link-search=native={}", library_path);
+        if target_os() == "macos" {
+            // `OpenCL.lib` on Windows, `libOpenCL.so`/`libOpenCL.dylib`
+            // elsewhere; the macOS SDK additionally ships it as a framework.
+            println!("cargo:rustc-link-lib=framework=OpenCL");
+        } else {
             println!("cargo:rustc-link-lib=dylib=OpenCL");
-            println!("cargo:rustc-link-search=native=##OPENCL_LIBRARY_PATH##");
         }
-        #[cfg(target_os = "macos")]
-        {
-            cc::Build::new()
-                .file("./lib_opencl/a.c")
-                .include("##OPENCL_INCLUDE_PATH##")
-                .flag("-fPIC")
-                .flag("-std=c99")
-                .shared_flag(true)
-                .compile("a");
-            println!("cargo:rustc-link-lib=framework=OpenCL");
-            println!("cargo:rustc-link-search=native=##OPENCL_LIBRARY_PATH##");
+    }
+
+    // Multicore (thread-pooled parallel CPU) support
+    #[cfg(feature = "multicore")]
+    {
+        let c_path = compile_futhark("lib_multicore", "multicore", &out_dir);
+        let mut build = cc::Build::new();
+        build.file(c_path).shared_flag(true).warnings(false);
+        apply_c_flags(&mut build);
+        build.compile("a");
+        if !target_is_msvc() {
+            println!("cargo:rustc-link-lib=dylib=pthread");
+        }
+    }
+
+    // ISPC support
+    #[cfg(feature = "ispc")]
+    {
+        let c_path = compile_futhark("lib_ispc", "ispc", &out_dir);
+
+        // `futhark ispc --library` writes the kernel source into OUT_DIR
+        // alongside `a.c`, not into the source tree, so it must be read
+        // from there rather than from `./lib_ispc`. Futhark names it
+        // `a.kernels.ispc`, not `a.ispc`.
+        let ispc_source = out_dir.join("lib_ispc/a.kernels.ispc");
+        let obj_ext = if target_is_msvc() { "obj" } else { "o" };
+        let obj_path = out_dir.join(format!("lib_ispc/a_ispc.{}", obj_ext));
+        let mut ispc_cmd = std::process::Command::new("ispc");
+        ispc_cmd.arg(&ispc_source).arg("-o").arg(&obj_path);
+        if !target_is_msvc() {
+            ispc_cmd.arg("--pic");
+        }
+        let ispc_status = ispc_cmd.status().expect("failed to run ispc compiler");
+        assert!(ispc_status.success(), "ispc compilation failed");
+
+        let mut build = cc::Build::new();
+        build
+            .file(c_path)
+            .object(obj_path)
+            .shared_flag(true)
+            .warnings(false);
+        apply_c_flags(&mut build);
+        build.compile("a");
+        // The ISPC backend runs on Futhark's multicore runtime and its
+        // thread scheduler, same as the `multicore` backend above.
+        if !target_is_msvc() {
+            println!("cargo:rustc-link-lib=dylib=pthread");
         }
     }
+
+    // Dependency-free stub support: no `futhark` invocation, just compile
+    // the no-op C source genfut already generated.
+    #[cfg(feature = "stub")]
+    {
+        let mut build = cc::Build::new();
+        build
+            .file("./lib_stub/a.c")
+            .shared_flag(true)
+            .warnings(false);
+        apply_c_flags(&mut build);
+        build.compile("a");
+    }
 }
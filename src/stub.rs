@@ -0,0 +1,381 @@
+//! Self-contained "stub" backend.
+//!
+//! Enabling the `stub` feature makes genfut synthesize a `lib_stub/a.c`
+//! directly from the parsed header, instead of asking `futhark` for one.
+//! Every `futhark_context*` function succeeds trivially, array
+//! constructors/destructors/accessors back a real host buffer sized to the
+//! caller's requested dimensions (so a round-trip through `_new` and
+//! `_values` reads back what was written in, rather than uninitialized
+//! memory), and every `futhark_entry_*` function returns a nonzero error
+//! code without touching a real backend. This lets a generated crate (and,
+//! combined with `no_futhark`, genfut itself) build, `cargo test` and
+//! `cargo doc` on a machine with no CUDA/OpenCL/Futhark toolchain at all.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::error::GenError;
+
+/// One `futhark_*` function prototype found in the real header.
+struct Prototype {
+    return_type: String,
+    name: String,
+    params: String,
+}
+
+fn parse_prototypes(headers: &str) -> Vec<Prototype> {
+    let re = Regex::new(r"(?m)^(\w[\w ]*?[ *]+)(futhark_\w+)\(([^;]*)\);")
+        .expect("[stub] prototype regex failed");
+    re.captures_iter(headers)
+        .map(|c| Prototype {
+            return_type: c[1].trim().to_owned(),
+            name: c[2].to_owned(),
+            params: c[3].trim().to_owned(),
+        })
+        .collect()
+}
+
+fn split_params(params: &str) -> Vec<&str> {
+    params
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn param_name(param: &str) -> String {
+    param
+        .rsplit(|c: char| c == ' ' || c == '*')
+        .next()
+        .unwrap_or(param)
+        .to_owned()
+}
+
+/// The C type a parameter points at, with `const` and `*` stripped, e.g.
+/// `const float *data` -> `float`, `int64_t dim0` -> `int64_t`.
+fn param_base_type(param: &str) -> String {
+    let type_end = param
+        .rfind(|c: char| c == ' ' || c == '*')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    param[..type_end]
+        .trim()
+        .trim_start_matches("const")
+        .trim()
+        .trim_end_matches('*')
+        .trim()
+        .to_owned()
+}
+
+/// Element type and rank of an array type, read straight from its
+/// `futhark_new_*` prototype's parameter list: `struct futhark_context
+/// *ctx, const float *data, int64_t dim0` gives element type `float`,
+/// rank 1. Futhark's own array constructors always take the context, a
+/// data pointer, then one `int64_t` dimension per rank, so matching that
+/// shape is enough to tell an array constructor apart from e.g.
+/// `futhark_context_new`, which doesn't share it.
+struct ArrayShape {
+    elem_ctype: String,
+    rank: usize,
+}
+
+fn parse_array_new(params: &str) -> Option<ArrayShape> {
+    let parts = split_params(params);
+    if parts.len() < 3 {
+        return None;
+    }
+    if !parts[2..].iter().all(|p| param_base_type(p) == "int64_t") {
+        return None;
+    }
+    Some(ArrayShape {
+        elem_ctype: param_base_type(parts[1]),
+        rank: parts.len() - 2,
+    })
+}
+
+/// Map from array type suffix (e.g. `f32_1d`, so that the full type name
+/// is `futhark_f32_1d`) to its shape, discovered from whichever `_new`
+/// prototype declares it.
+fn array_shapes(prototypes: &[Prototype]) -> BTreeMap<String, ArrayShape> {
+    prototypes
+        .iter()
+        .filter_map(|proto| {
+            let suffix = proto.name.strip_prefix("futhark_new_")?;
+            let shape = parse_array_new(&proto.params)?;
+            Some((suffix.to_owned(), shape))
+        })
+        .collect()
+}
+
+/// A real struct definition for `futhark_<suffix>`, backing the opaque
+/// type the header only forward-declares, the same way a real backend's
+/// generated `a.c` would.
+fn array_struct_def(suffix: &str, shape: &ArrayShape) -> String {
+    format!(
+        "struct futhark_{suffix} {{\n    {elem} *data;\n    int64_t shape[{rank}];\n}};\n\n",
+        suffix = suffix,
+        elem = shape.elem_ctype,
+        rank = shape.rank
+    )
+}
+
+/// Body for an array constructor: allocate a buffer sized to the
+/// requested dimensions and copy the caller's data into it.
+fn array_new_body(proto: &Prototype, suffix: &str, shape: &ArrayShape) -> String {
+    let parts = split_params(&proto.params);
+    let data_name = param_name(parts[1]);
+    let dim_names: Vec<String> = parts[2..].iter().map(|p| param_name(p)).collect();
+    format!(
+        "int64_t shape[{rank}] = {{ {dims} }};\n    \
+         int64_t len = 1;\n    \
+         for (size_t i = 0; i < {rank}; i++) {{ len *= shape[i]; }}\n    \
+         struct futhark_{suffix} *arr = malloc(sizeof(struct futhark_{suffix}));\n    \
+         memcpy(arr->shape, shape, sizeof(shape));\n    \
+         arr->data = malloc(len * sizeof({elem}));\n    \
+         memcpy(arr->data, {data}, len * sizeof({elem}));\n    \
+         return arr;",
+        rank = shape.rank,
+        dims = dim_names.join(", "),
+        suffix = suffix,
+        elem = shape.elem_ctype,
+        data = data_name
+    )
+}
+
+/// Body for an array destructor: free the backing buffer and the struct.
+fn array_free_body(proto: &Prototype) -> String {
+    let parts = split_params(&proto.params);
+    let arr_name = param_name(parts[1]);
+    format!(
+        "free({arr}->data);\n    free({arr});\n    return 0;",
+        arr = arr_name
+    )
+}
+
+/// Body for a `futhark_values_*` read-back: copy the backing buffer into
+/// the caller-supplied output buffer.
+fn array_values_body(proto: &Prototype, shape: &ArrayShape) -> String {
+    let parts = split_params(&proto.params);
+    let arr_name = param_name(parts[1]);
+    let out_name = param_name(parts[2]);
+    format!(
+        "int64_t len = 1;\n    \
+         for (size_t i = 0; i < {rank}; i++) {{ len *= {arr}->shape[i]; }}\n    \
+         memcpy({out}, {arr}->data, len * sizeof({elem}));\n    \
+         return 0;",
+        rank = shape.rank,
+        arr = arr_name,
+        out = out_name,
+        elem = shape.elem_ctype
+    )
+}
+
+/// Body for a `futhark_shape_*` accessor: hand back the stored shape.
+fn array_shape_body(proto: &Prototype) -> String {
+    let parts = split_params(&proto.params);
+    let arr_name = param_name(parts[1]);
+    format!("return {arr}->shape;", arr = arr_name)
+}
+
+/// Specialized body for one of an array type's constructor/destructor/
+/// accessor functions, if `proto` is one and its type is in `shapes`.
+fn array_stub_body(proto: &Prototype, shapes: &BTreeMap<String, ArrayShape>) -> Option<String> {
+    if let Some(suffix) = proto.name.strip_prefix("futhark_new_") {
+        return shapes
+            .get(suffix)
+            .map(|shape| array_new_body(proto, suffix, shape));
+    }
+    if let Some(suffix) = proto.name.strip_prefix("futhark_free_") {
+        return shapes.get(suffix).map(|_| array_free_body(proto));
+    }
+    if let Some(suffix) = proto.name.strip_prefix("futhark_values_") {
+        return shapes
+            .get(suffix)
+            .map(|shape| array_values_body(proto, shape));
+    }
+    if let Some(suffix) = proto.name.strip_prefix("futhark_shape_") {
+        return shapes.get(suffix).map(|_| array_shape_body(proto));
+    }
+    None
+}
+
+/// Produce a no-op definition for `proto`, standing in for whatever a real
+/// backend would otherwise have implemented.
+fn stub_body(proto: &Prototype) -> String {
+    let returns_void = proto.return_type == "void";
+    let returns_pointer = proto.return_type.ends_with('*');
+    if proto.name.starts_with("futhark_entry_") {
+        // Entry points never compute anything; report a (recoverable)
+        // runtime failure rather than silently returning garbage data.
+        return "return 1;".to_string();
+    }
+    if proto.name.contains("_new") {
+        // Context/config constructors: a bare heap allocation is enough
+        // to give callers a distinct, freeable pointer. Array
+        // constructors are handled separately by `array_stub_body`.
+        return "return calloc(1, 1);".to_string();
+    }
+    if proto.name.contains("_free") {
+        let free_stmt = format!(
+            "free({});",
+            last_param_name(&proto.params).unwrap_or_else(|| "NULL".to_string())
+        );
+        return if returns_void {
+            free_stmt
+        } else {
+            format!("{}\n    return 0;", free_stmt)
+        };
+    }
+    if proto.name.contains("get_error") {
+        return "return NULL;".to_string();
+    }
+    if returns_void {
+        "return;".to_string()
+    } else if returns_pointer {
+        "return NULL;".to_string()
+    } else {
+        "return 0;".to_string()
+    }
+}
+
+fn last_param_name(params: &str) -> Option<String> {
+    let last = params.split(',').last()?.trim();
+    last.rsplit(|c: char| c == ' ' || c == '*')
+        .next()
+        .map(str::to_owned)
+}
+
+/// Generate `lib_stub/a.c` and `lib_stub/a.h`, a compilable no-op
+/// implementation of every symbol declared in `headers`, and the header
+/// itself so `a.c`'s `#include "a.h"` resolves. Without the latter, only
+/// the `no_futhark + stub` combination would build, since it alone reads
+/// a header pre-committed from a previous real run.
+pub(crate) fn gen_stub_c(headers: &str, out_dir: &Path) -> Result<(), GenError> {
+    let lib_dir = out_dir.join("lib_stub");
+    std::fs::create_dir_all(&lib_dir).map_err(|e| GenError::Io {
+        path: lib_dir.clone(),
+        source: e,
+    })?;
+
+    let header_path = lib_dir.join("a.h");
+    std::fs::write(&header_path, headers).map_err(|e| GenError::Io {
+        path: header_path,
+        source: e,
+    })?;
+
+    let prototypes = parse_prototypes(headers);
+    let shapes = array_shapes(&prototypes);
+
+    let mut src = String::new();
+    src.push_str(
+        "// Auto-generated by genfut's `stub` feature: no real computation happens here.\n",
+    );
+    src.push_str("#include \"a.h\"\n#include <stdlib.h>\n#include <string.h>\n\n");
+
+    for (suffix, shape) in &shapes {
+        src.push_str(&array_struct_def(suffix, shape));
+    }
+
+    for proto in &prototypes {
+        let body = array_stub_body(proto, &shapes).unwrap_or_else(|| stub_body(proto));
+        src.push_str(&format!(
+            "{} {}({}) {{\n    {}\n}}\n\n",
+            proto.return_type,
+            proto.name,
+            if proto.params.is_empty() {
+                "void".to_string()
+            } else {
+                proto.params.clone()
+            },
+            body
+        ));
+    }
+
+    let c_path = lib_dir.join("a.c");
+    std::fs::write(&c_path, src).map_err(|e| GenError::Io {
+        path: c_path,
+        source: e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shaped like a real Futhark `--library` header: context/config
+    /// boilerplate, one array type, and one entry point.
+    const SAMPLE_HEADER: &str = "\
+struct futhark_context_config;
+struct futhark_context_config *futhark_context_config_new(void);
+void futhark_context_config_free(struct futhark_context_config *cfg);
+struct futhark_context;
+struct futhark_context *futhark_context_new(struct futhark_context_config *cfg);
+void futhark_context_free(struct futhark_context *ctx);
+int futhark_context_sync(struct futhark_context *ctx);
+char *futhark_context_get_error(struct futhark_context *ctx);
+
+struct futhark_f32_1d;
+struct futhark_f32_1d *futhark_new_f32_1d(struct futhark_context *ctx, const float *data, int64_t dim0);
+int futhark_free_f32_1d(struct futhark_context *ctx, struct futhark_f32_1d *arr);
+int futhark_values_f32_1d(struct futhark_context *ctx, struct futhark_f32_1d *arr, float *data);
+const int64_t *futhark_shape_f32_1d(struct futhark_context *ctx, struct futhark_f32_1d *arr);
+
+int futhark_entry_main(struct futhark_context *ctx, float *out0, const struct futhark_f32_1d *in0);
+";
+
+    #[test]
+    fn parse_prototypes_finds_every_declaration() {
+        assert_eq!(parse_prototypes(SAMPLE_HEADER).len(), 11);
+    }
+
+    #[test]
+    fn array_shapes_discovers_rank_and_elem_type() {
+        let shapes = array_shapes(&parse_prototypes(SAMPLE_HEADER));
+        let shape = shapes.get("f32_1d").expect("f32_1d not discovered");
+        assert_eq!(shape.elem_ctype, "float");
+        assert_eq!(shape.rank, 1);
+    }
+
+    #[test]
+    fn stub_body_void_free_has_no_return_value() {
+        let proto = Prototype {
+            return_type: "void".to_string(),
+            name: "futhark_context_free".to_string(),
+            params: "struct futhark_context *ctx".to_string(),
+        };
+        assert_eq!(stub_body(&proto), "free(ctx);");
+    }
+
+    #[test]
+    fn stub_body_int_free_returns_zero() {
+        let proto = Prototype {
+            return_type: "int".to_string(),
+            name: "futhark_context_config_free".to_string(),
+            params: "struct futhark_context_config *cfg".to_string(),
+        };
+        assert_eq!(stub_body(&proto), "free(cfg);\n    return 0;");
+    }
+
+    #[test]
+    fn gen_stub_c_writes_header_and_backs_array_round_trip() {
+        let out_dir = std::env::temp_dir().join("genfut_stub_test_round_trip");
+        gen_stub_c(SAMPLE_HEADER, &out_dir).expect("gen_stub_c failed");
+
+        let header =
+            std::fs::read_to_string(out_dir.join("lib_stub/a.h")).expect("a.h not written");
+        assert_eq!(header, SAMPLE_HEADER);
+
+        let source =
+            std::fs::read_to_string(out_dir.join("lib_stub/a.c")).expect("a.c not written");
+        assert!(source.contains("struct futhark_f32_1d {"));
+        assert!(source.contains("arr->data = malloc(len * sizeof(float));"));
+        assert!(source.contains("memcpy(arr->data, data, len * sizeof(float));"));
+        assert!(source.contains("memcpy(data, arr->data, len * sizeof(float));"));
+        assert!(source.contains("return arr->shape;"));
+        assert!(source.contains("free(arr->data);\n    free(arr);\n    return 0;"));
+
+        std::fs::remove_dir_all(&out_dir).ok();
+    }
+}